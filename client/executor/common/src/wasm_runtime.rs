@@ -0,0 +1,154 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Definitions for a wasm runtime.
+
+use sp_wasm_interface::Value;
+use crate::error::Error;
+
+/// A trait that defines an abstract wasm runtime module instance.
+///
+/// This can be implemented by an execution engine.
+pub trait WasmInstance: Send {
+	/// Perform a call into the given method as if it was exported.
+	fn call(&mut self, method: &str, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+	/// Perform a call into the given method as if it was exported, without requiring a mutable
+	/// reference to the instance.
+	fn call_export(&self, method: &str, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+	/// Get the value from a global with the given `name`.
+	///
+	/// This method is only suitable for getting immutable globals.
+	fn get_global_const(&mut self, name: &str) -> Result<Option<Value>, Error>;
+
+	/// Return the base address of the linear memory, if the instance exposes one.
+	fn linear_memory_base_ptr(&self) -> Option<*const u8> {
+		None
+	}
+
+	/// Return the number of bytes of the linear memory that are currently backed by physical
+	/// memory (its "resident set"), if this can be determined on the current platform.
+	///
+	/// This is an approximation intended for memory-pressure metrics and for detecting pages that
+	/// stay backed by physical memory after a call finishes; it is `None` wherever the underlying
+	/// platform doesn't let us probe this cheaply.
+	fn linear_memory_resident_bytes(&self) -> Option<usize> {
+		let base = self.linear_memory_base_ptr()?;
+
+		#[cfg(target_os = "macos")]
+		return self::macos::resident_bytes(base);
+
+		#[cfg(target_os = "linux")]
+		return self::linux::resident_bytes(base);
+
+		#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+		{
+			let _ = base;
+			None
+		}
+	}
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+	// Needs `mach` declared under this crate's `[target.'cfg(target_os = "macos")'.dependencies]`.
+	use std::convert::TryInto;
+	use mach::{
+		kern_return::KERN_SUCCESS,
+		traps::mach_task_self,
+		vm::mach_vm_region,
+		vm_page_size::vm_page_shift,
+		vm_region::{vm_region_extended_info, vm_region_info_t, VM_REGION_EXTENDED_INFO},
+		vm_types::{mach_vm_address_t, mach_vm_size_t},
+	};
+
+	/// Probe the resident size of the VMA containing `base`, using `mach_vm_region`.
+	pub fn resident_bytes(base: *const u8) -> Option<usize> {
+		let mut addr: mach_vm_address_t = (base as usize).try_into().ok()?;
+		let mut size: mach_vm_size_t = 0;
+		let mut info = std::mem::MaybeUninit::<vm_region_extended_info>::uninit();
+
+		let result = unsafe {
+			mach_vm_region(
+				mach_task_self(),
+				&mut addr,
+				&mut size,
+				VM_REGION_EXTENDED_INFO,
+				info.as_mut_ptr() as vm_region_info_t,
+				&mut vm_region_extended_info::count(),
+				&mut 0,
+			)
+		};
+
+		if result != KERN_SUCCESS {
+			return None
+		}
+
+		let info = unsafe { info.assume_init() };
+		let resident_bytes = unsafe { info.pages_resident << vm_page_shift };
+		resident_bytes.try_into().ok()
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use std::fs;
+
+	/// Probe the `Rss` field of the `/proc/self/smaps` entry for the VMA containing `base`.
+	pub fn resident_bytes(base: *const u8) -> Option<usize> {
+		let base = base as usize;
+		let smaps = fs::read_to_string("/proc/self/smaps").ok()?;
+
+		let mut lines = smaps.lines().peekable();
+		while let Some(header) = lines.next() {
+			// Only VMA headers start with a `start-end` address range; the field lines of VMAs that
+			// don't contain `base` are skipped here.
+			let range = header.split_whitespace().next().unwrap_or("");
+			let (start, end) = match range.split_once('-') {
+				Some(bounds) => bounds,
+				None => continue,
+			};
+			let (start, end) = match (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) {
+				(Ok(start), Ok(end)) => (start, end),
+				_ => continue,
+			};
+
+			if base < start || base >= end {
+				continue
+			}
+
+			// Scan the fields of this VMA until we either find `Rss` or hit the next VMA header
+			// (recognisable by its first token being an `start-end` address range).
+			while let Some(line) = lines.peek() {
+				if let Some(rest) = line.strip_prefix("Rss:") {
+					let kb: usize = rest.trim_end_matches("kB").trim().parse().ok()?;
+					return kb.checked_mul(1024)
+				}
+				let first_token = line.split_whitespace().next().unwrap_or("");
+				if first_token.split_once('-').is_some() {
+					break
+				}
+				lines.next();
+			}
+			return None
+		}
+
+		None
+	}
+}
@@ -23,32 +23,19 @@
 // borthersome.
 #![cfg(feature = "wasmtime")]
 
-#[cfg(target_os = "linux")]
-mod linux;
-
-#[cfg(target_os = "linux")]
-use linux::*;
-
-#[cfg(target_os = "macos")]
-mod macos;
-
-#[cfg(target_os = "macos")]
-use macos::*;
-
 use crate::{
 	WasmExecutionMethod,
 	integration_tests::mk_test_runtime,
 };
 use codec::Encode as _;
-#[cfg(target_os = "linux")]
-use linux::*;
+use sc_executor_common::wasm_runtime::WasmInstance;
 
 #[test]
 fn memory_consumption_compiled() {
 	// This aims to see if linear memory stays backed by the physical memory after a runtime call.
 	//
 	// For that we make a series of runtime calls, probing the RSS for the VMA matching the linear
-	// memory. After the call we expect RSS to be equal to 0.
+	// memory. After the call we expect the resident size to be equal to 0.
 
 	let runtime = mk_test_runtime(WasmExecutionMethod::Compiled, 1024);
 
@@ -66,15 +53,15 @@ fn memory_consumption_compiled() {
 			&(heap_base as u32, 1u32).encode(),
 		)
 		.unwrap();
-	let probe_1 = instance_resident_bytes(&*instance);
+	let probe_1 = instance.linear_memory_resident_bytes();
 	instance
 		.call_export(
 			"test_dirty_plenty_memory",
 			&(heap_base as u32, 1024u32).encode(),
 		)
 		.unwrap();
-	let probe_2 = instance_resident_bytes(&*instance);
+	let probe_2 = instance.linear_memory_resident_bytes();
 
-	assert_eq!(probe_1, 0);
-	assert_eq!(probe_2, 0);
+	assert_eq!(probe_1, Some(0));
+	assert_eq!(probe_2, Some(0));
 }
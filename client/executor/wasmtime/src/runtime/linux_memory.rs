@@ -0,0 +1,206 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Defines a custom memory allocator for allocating host memory for wasm linear memories on
+//! Linux, mirroring `mach_memory` so the accessible pages can be handed back to the kernel
+//! between runtime calls.
+
+// Needs `libc` declared under this crate's `[target.'cfg(unix)'.dependencies]`.
+use crate::runtime::reset::ResetLinearMemory;
+use wasmtime::{MemoryCreator, LinearMemory, MemoryType};
+use std::sync::{Arc, Mutex, Weak};
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+pub struct LinuxAllocator {
+	/// Weak handles to every memory this allocator has created, so [`reset_memories`] can return
+	/// their resident pages to the OS without the allocator having to own the instance that
+	/// holds them.
+	///
+	/// [`reset_memories`]: Self::reset_memories
+	memories: Mutex<Vec<Weak<LinuxMemory>>>,
+}
+
+pub struct LinuxMemory {
+	/// The base address of the `mmap`ed region.
+	address: *mut libc::c_void,
+	/// The size of the mapping created in bytes.
+	///
+	/// Growing this memory beyond this size is not supported: `grow` panics instead of allocating a
+	/// new mapping.
+	mapped_bytes: u64,
+	/// Size of the guard pages in bytes.
+	guard_bytes: u64,
+	/// The currently accessible number of wasm pages.
+	///
+	/// Starting with wasmtime 0.28 we can remove the mutex as `LinearMemory::grow` takes
+	/// an exclusive reference to this struct.
+	wasm_pages: Mutex<u32>,
+	/// The maximum number of wasm pages this memory is allowed to be grown to.
+	wasm_pages_max: Option<u32>,
+}
+
+// SAFETY: the raw pointer only ever refers to memory we privately `mmap`ed; access to the
+// accessible size is synchronized through `wasm_pages`.
+unsafe impl Send for LinuxMemory {}
+unsafe impl Sync for LinuxMemory {}
+
+impl LinuxAllocator {
+	pub fn new() -> Self {
+		Self {
+			memories: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Return the resident pages of every memory this allocator is still tracking to the
+	/// operating system.
+	///
+	/// This is meant to be called after a runtime call returns and before the instance that owns
+	/// these memories is handed out for reuse, so its heap starts the next call with bounded
+	/// resident memory instead of whatever was left dirty by the previous one.
+	pub fn reset_memories(&self) {
+		let mut memories = self.memories.lock().unwrap();
+		memories.retain(|memory| {
+			match memory.upgrade() {
+				Some(memory) => {
+					memory.reset();
+					true
+				},
+				// The memory has been dropped along with its instance; stop tracking it.
+				None => false,
+			}
+		});
+	}
+}
+
+unsafe impl MemoryCreator for LinuxAllocator {
+    fn new_memory(
+        &self,
+        ty: MemoryType,
+        reserved_size_in_bytes: Option<u64>,
+        guard_size_in_bytes: u64
+    ) -> Result<Box<dyn LinearMemory>, String> {
+		let accessible_bytes = u64::from(ty.limits().min()) * WASM_PAGE_SIZE;
+		let mapped_bytes = reserved_size_in_bytes
+			.unwrap_or(accessible_bytes)
+			.checked_add(guard_size_in_bytes)
+			.ok_or_else(|| "Guard size overflowed u64".to_string())?;
+
+		assert!(accessible_bytes <= mapped_bytes);
+
+		// SAFETY: mapping anonymous, private memory is always safe; we let the kernel pick the
+		// address since we pass a null hint.
+		let address = unsafe {
+			libc::mmap(
+				std::ptr::null_mut(),
+				mapped_bytes as usize,
+				libc::PROT_NONE,
+				libc::MAP_PRIVATE | libc::MAP_ANON,
+				-1,
+				0,
+			)
+		};
+		if address == libc::MAP_FAILED {
+			return Err(format!("mmap returned an error: {}", std::io::Error::last_os_error()));
+		}
+
+		// Make the accessible region read-write; the guard region stays `PROT_NONE`.
+		let result = unsafe {
+			libc::mprotect(address, accessible_bytes as usize, libc::PROT_READ | libc::PROT_WRITE)
+		};
+		assert_eq!(result, 0);
+
+		let memory = Arc::new(LinuxMemory {
+			address,
+			mapped_bytes,
+			guard_bytes: guard_size_in_bytes,
+			wasm_pages: Mutex::new(ty.limits().min()),
+			wasm_pages_max: ty.limits().max(),
+		});
+		self.memories.lock().unwrap().push(Arc::downgrade(&memory));
+		Ok(Box::new(LinuxMemoryHandle(memory)))
+	}
+}
+
+/// The handle wasmtime actually holds; cheaply delegates to the shared [`LinuxMemory`], a second
+/// `Arc` of which [`LinuxAllocator`] keeps so it can reset resident pages between calls even while
+/// wasmtime owns this `Box<dyn LinearMemory>`.
+struct LinuxMemoryHandle(Arc<LinuxMemory>);
+
+unsafe impl LinearMemory for LinuxMemoryHandle {
+	fn size(&self) -> u32 {
+		self.0.size()
+	}
+
+	fn maximum(&self) -> Option<u32> {
+		self.0.maximum()
+	}
+
+	fn grow(&self, delta: u32) -> Option<u32> {
+		self.0.grow(delta)
+	}
+
+	fn as_ptr(&self) -> *mut u8 {
+		self.0.as_ptr()
+	}
+}
+
+unsafe impl LinearMemory for LinuxMemory {
+    fn size(&self) -> u32 {
+		*self.wasm_pages.lock().unwrap()
+	}
+
+    fn maximum(&self) -> Option<u32> {
+		self.wasm_pages_max
+	}
+
+    fn grow(&self, delta: u32) -> Option<u32> {
+		let mut wasm_pages = self.wasm_pages.lock().unwrap();
+		let new_page_num = wasm_pages.checked_add(delta)?;
+		match self.wasm_pages_max {
+			Some(max) if new_page_num > max => return None,
+			_ => (),
+		}
+		let new_bytes = (new_page_num as u64) * WASM_PAGE_SIZE;
+		// for now we do not support reallocating
+		assert!(new_bytes.checked_add(self.guard_bytes)? <= self.mapped_bytes);
+		let result = unsafe {
+			libc::mprotect(self.address, new_bytes as usize, libc::PROT_READ | libc::PROT_WRITE)
+		};
+		assert_eq!(result, 0);
+		*wasm_pages = new_page_num;
+		Some(new_page_num)
+	}
+
+    fn as_ptr(&self) -> *mut u8 {
+		self.address as _
+	}
+}
+
+impl ResetLinearMemory for LinuxMemory {
+	fn reset(&self) {
+		let accessible_bytes = (u64::from(self.size())) * WASM_PAGE_SIZE;
+		// SAFETY: `[address, address + accessible_bytes)` is entirely within our own mapping.
+		// `MADV_DONTNEED` on an anonymous mapping drops the resident pages; the next access
+		// zero-fills them on demand.
+		let result = unsafe {
+			libc::madvise(self.address, accessible_bytes as usize, libc::MADV_DONTNEED)
+		};
+		assert_eq!(result, 0);
+	}
+}
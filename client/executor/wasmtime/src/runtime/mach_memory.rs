@@ -23,36 +23,58 @@ use mach::{
     kern_return::KERN_SUCCESS,
     traps::mach_task_self,
 	port::mach_port_t,
-    vm::{mach_vm_allocate, mach_vm_protect},
+    vm::{mach_vm_allocate, mach_vm_deallocate, mach_vm_protect, mach_vm_purgable_control},
     vm_types::{mach_vm_address_t, mach_vm_size_t},
     vm_prot::{vm_prot_t, VM_PROT_NONE, VM_PROT_DEFAULT},
+    vm_purgable::{VM_PURGABLE_EMPTY, VM_PURGABLE_NONVOLATILE, VM_PURGABLE_SET_STATE},
 };
 use wasmtime::{MemoryCreator, LinearMemory, MemoryType};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use crate::runtime::reset::ResetLinearMemory;
 
 const WASM_PAGE_SHIFT: u64 = 16;
 
 pub struct MachAllocator {
 	task: mach_port_t,
+	/// Weak handles to every memory this allocator has created, so [`reset_memories`] can return
+	/// their resident pages to the OS without the allocator having to own the instance that
+	/// holds them.
+	///
+	/// [`reset_memories`]: Self::reset_memories
+	memories: Mutex<Vec<Weak<MachMemory>>>,
 }
 
 pub struct MachMemory {
 	/// The virtual address of the mapping.
-	address: mach_vm_address_t,
+	///
+	/// Growing beyond `mapped_bytes` replaces this with a freshly allocated mapping, so it is
+	/// stored atomically to be updated in place from behind a shared reference.
+	address: AtomicU64,
 	/// The size of the mapping created in bytes.
 	///
 	/// If this memory is grown beyond the virtual size we need to allocate a new
 	/// a new mapping and copy over.
-	mapped_bytes: u64,
+	mapped_bytes: AtomicU64,
 	/// Size of the guard pages in bytes.
 	guard_bytes: u64,
 	/// The currently accesible number of wasm pages.
 	///
-	/// Starting with wasmtime 0.28 we can remove the mutex as `LinearMeory::grow` takes
-	/// an exclusive reference to this struct.
-	wasm_pages: Mutex<u32>,
+	/// Read lock-free through `size()`/`maximum()`. Every mutation of it is performed under
+	/// `realloc_lock`, alongside the mapping itself, so the two can never be observed out of sync
+	/// by a concurrent `grow`.
+	wasm_pages: AtomicU32,
 	/// The maximum number was wasm pages this memory is allowed to be growed to.
 	wasm_pages_max: Option<u32>,
+	/// Serializes `grow`.
+	///
+	/// `grow` may need to reallocate the mapping (`address`/`mapped_bytes`) in addition to
+	/// bumping `wasm_pages`, and those two things have to happen as one atomic unit: two threads
+	/// racing through the reallocation branch would both copy from (and then both deallocate) the
+	/// same old mapping. This is unconditional, matching the unconditional `Mutex` this type used
+	/// to wrap `wasm_pages` in before it was split into atomics: `grow` is safe to call
+	/// concurrently from several executor worker threads in every build, not just an opt-in one.
+	realloc_lock: Mutex<()>,
 }
 
 impl MachAllocator {
@@ -62,8 +84,29 @@ impl MachAllocator {
 		let task = unsafe { mach_task_self() };
 		Ok(Self {
 			task,
+			memories: Mutex::new(Vec::new()),
 		})
 	}
+
+	/// Return the resident pages of every memory this allocator is still tracking to the
+	/// operating system.
+	///
+	/// This is meant to be called after a runtime call returns and before the instance that owns
+	/// these memories is handed out for reuse, so its heap starts the next call with bounded
+	/// resident memory instead of whatever was left dirty by the previous one.
+	pub fn reset_memories(&self) {
+		let mut memories = self.memories.lock().unwrap();
+		memories.retain(|memory| {
+			match memory.upgrade() {
+				Some(memory) => {
+					memory.reset();
+					true
+				},
+				// The memory has been dropped along with its instance; stop tracking it.
+				None => false,
+			}
+		});
+	}
 }
 
 unsafe impl MemoryCreator for MachAllocator {
@@ -104,20 +147,45 @@ unsafe impl MemoryCreator for MachAllocator {
 			VM_PROT_NONE,
 		);
 
-		let result = Box::new(MachMemory {
-			address,
-			mapped_bytes,
+		let memory = Arc::new(MachMemory {
+			address: AtomicU64::new(address),
+			mapped_bytes: AtomicU64::new(mapped_bytes),
 			guard_bytes: guard_size_in_bytes,
-			wasm_pages: Mutex::new(ty.limits().min()),
+			wasm_pages: AtomicU32::new(ty.limits().min()),
 			wasm_pages_max: ty.limits().max(),
+			realloc_lock: Mutex::new(()),
 		});
-		Ok(result)
+		self.memories.lock().unwrap().push(Arc::downgrade(&memory));
+		Ok(Box::new(MachMemoryHandle(memory)))
+	}
+}
+
+/// The handle wasmtime actually holds; cheaply delegates to the shared [`MachMemory`], a second
+/// `Arc` of which [`MachAllocator`] keeps so it can reset resident pages between calls even while
+/// wasmtime owns this `Box<dyn LinearMemory>`.
+struct MachMemoryHandle(Arc<MachMemory>);
+
+unsafe impl LinearMemory for MachMemoryHandle {
+	fn size(&self) -> u32 {
+		self.0.size()
+	}
+
+	fn maximum(&self) -> Option<u32> {
+		self.0.maximum()
+	}
+
+	fn grow(&self, delta: u32) -> Option<u32> {
+		self.0.grow(delta)
+	}
+
+	fn as_ptr(&self) -> *mut u8 {
+		self.0.as_ptr()
 	}
 }
 
 unsafe impl LinearMemory for MachMemory {
     fn size(&self) -> u32 {
-		*self.wasm_pages.lock().unwrap()
+		self.wasm_pages.load(Ordering::SeqCst)
 	}
 
     fn maximum(&self) -> Option<u32> {
@@ -125,22 +193,95 @@ unsafe impl LinearMemory for MachMemory {
 	}
 
     fn grow(&self, delta: u32) -> Option<u32> {
-		let mut wasm_pages = self.wasm_pages.lock().unwrap();
+		// Hold `realloc_lock` across the whole operation: reading `wasm_pages`, possibly
+		// reallocating the mapping, and writing the new `wasm_pages` all have to happen as one
+		// atomic unit, since `grow_reservation` mutates `address` and `mapped_bytes` too, not just
+		// the page counter.
+		let _guard = self.realloc_lock.lock().unwrap();
+
+		let wasm_pages = self.wasm_pages.load(Ordering::SeqCst);
+		let new_page_num = self.checked_new_page_num(wasm_pages, delta)?;
+		self.grow_reservation(wasm_pages, new_page_num)?;
+		self.wasm_pages.store(new_page_num, Ordering::SeqCst);
+		Some(new_page_num)
+	}
+
+    fn as_ptr(&self) -> *mut u8 {
+		self.address.load(Ordering::SeqCst) as _
+	}
+}
+
+impl MachMemory {
+	/// Compute the new page count for a `grow(delta)` call, checking it against `wasm_pages_max`.
+	fn checked_new_page_num(&self, wasm_pages: u32, delta: u32) -> Option<u32> {
 		let new_page_num = wasm_pages.checked_add(delta)?;
 		match self.wasm_pages_max {
-			Some(max) if new_page_num > max => return None,
-			_ => (),
+			Some(max) if new_page_num > max => None,
+			_ => Some(new_page_num),
 		}
+	}
+
+	/// Ensure the mapping can hold `new_page_num` accessible wasm pages, reallocating if the
+	/// current reservation is too small, and apply the new protection.
+	fn grow_reservation(&self, wasm_pages: u32, new_page_num: u32) -> Option<()> {
 		let new_bytes = (new_page_num as u64) << WASM_PAGE_SHIFT;
-		// for now we do not support reallocating
-		assert!(new_bytes.checked_add(self.guard_bytes)? > self.mapped_bytes);
-		*wasm_pages = new_page_num;
-		change_protection(self.address, new_bytes, VM_PROT_DEFAULT);
-		Some(new_page_num)
+		let new_mapped_bytes = new_bytes.checked_add(self.guard_bytes)?;
+		let mapped_bytes = self.mapped_bytes.load(Ordering::SeqCst);
+
+		if new_mapped_bytes <= mapped_bytes {
+			// Fast path: the new accessible size still fits in the region we already reserved.
+			change_protection(self.address.load(Ordering::SeqCst), new_bytes, VM_PROT_DEFAULT);
+		} else {
+			// The growth no longer fits; allocate a fresh, larger mapping and copy the existing
+			// accessible bytes over.
+			let address = self.address.load(Ordering::SeqCst);
+			let accessible_bytes = (wasm_pages as u64) << WASM_PAGE_SHIFT;
+
+			let mut new_address: mach_vm_address_t = 0;
+			let result = unsafe {
+				mach_vm_allocate(
+					mach_task_self(),
+					&mut new_address,
+					new_mapped_bytes,
+					1 | 2,
+				)
+			};
+			if result != KERN_SUCCESS {
+				return None;
+			}
+
+			// SAFETY: `address` is a valid mapping of at least `accessible_bytes` and
+			// `new_address` is the fresh mapping we just allocated, which is at least
+			// `new_mapped_bytes >= accessible_bytes` large.
+			unsafe {
+				std::ptr::copy_nonoverlapping(
+					address as *const u8,
+					new_address as *mut u8,
+					accessible_bytes as usize,
+				);
+			}
+
+			// Block out the guard pages in the new mapping.
+			change_protection(new_address + new_bytes, new_mapped_bytes - new_bytes, VM_PROT_NONE);
+
+			let dealloc_result = unsafe { mach_vm_deallocate(mach_task_self(), address, mapped_bytes) };
+			assert_eq!(dealloc_result, KERN_SUCCESS);
+
+			self.address.store(new_address, Ordering::SeqCst);
+			self.mapped_bytes.store(new_mapped_bytes, Ordering::SeqCst);
+		}
+
+		Some(())
 	}
+}
 
-    fn as_ptr(&self) -> *mut u8 {
-		self.address as _
+impl ResetLinearMemory for MachMemory {
+	fn reset(&self) {
+		// Drop the resident pages backing the accessible region, then immediately mark the
+		// mapping non-volatile again so it is ready to be written to on the next call.
+		let address = self.address.load(Ordering::SeqCst);
+		set_purgable_state(address, VM_PURGABLE_EMPTY);
+		set_purgable_state(address, VM_PURGABLE_NONVOLATILE);
 	}
 }
 
@@ -156,3 +297,15 @@ fn change_protection(addr: mach_vm_address_t, size: mach_vm_size_t, prot: vm_pro
 	};
 	assert_eq!(result, 0);
 }
+
+fn set_purgable_state(addr: mach_vm_address_t, mut state: i32) {
+	let result = unsafe {
+		mach_vm_purgable_control(
+			mach_task_self(),
+			addr,
+			VM_PURGABLE_SET_STATE,
+			&mut state,
+		)
+	};
+	assert_eq!(result, KERN_SUCCESS);
+}
@@ -0,0 +1,77 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(target_os = "macos")]
+pub mod mach_memory;
+#[cfg(target_os = "linux")]
+pub mod linux_memory;
+pub mod reset;
+
+#[cfg(target_os = "macos")]
+use mach_memory::MachAllocator as PlatformAllocator;
+#[cfg(target_os = "linux")]
+use linux_memory::LinuxAllocator as PlatformAllocator;
+
+/// Configure `config` to allocate wasm linear memory through this platform's [`MemoryCreator`],
+/// returning the allocator it was given.
+///
+/// Keep the returned allocator alive alongside the `wasmtime::Instance`s built from `config`, and
+/// use it to build the `reset` closure passed to [`reset::ResettingInstance::new`] for each of
+/// them, e.g. `{ let allocator = allocator.clone(); move || allocator.reset_memories() }`.
+///
+/// [`MemoryCreator`]: wasmtime::MemoryCreator
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub fn with_platform_allocator(
+	config: &mut wasmtime::Config,
+) -> Result<std::sync::Arc<PlatformAllocator>, String> {
+	#[cfg(target_os = "macos")]
+	let allocator = std::sync::Arc::new(PlatformAllocator::new()?);
+	#[cfg(target_os = "linux")]
+	let allocator = std::sync::Arc::new(PlatformAllocator::new());
+
+	config.with_host_memory(allocator.clone());
+	Ok(allocator)
+}
+
+#[cfg(all(test, any(target_os = "macos", target_os = "linux")))]
+mod test {
+	use super::*;
+	use wasmtime::{Config, Engine, Store, Memory, MemoryType, Limits};
+
+	#[test]
+	fn platform_allocator_is_actually_used_by_wasmtime() {
+		let mut config = Config::new();
+		let allocator = with_platform_allocator(&mut config).unwrap();
+
+		let engine = Engine::new(&config);
+		let store = Store::new(&engine);
+		let memory = Memory::new(&store, MemoryType::new(Limits::new(1, Some(2))));
+
+		assert_eq!(memory.size(), 1);
+		memory.grow(1).unwrap();
+		assert_eq!(memory.size(), 2);
+
+		// The memory created above is backed and tracked by our allocator, not wasmtime's default
+		// one: resetting the allocator keeps its size but hands its pages back to the OS, so a byte
+		// written before the reset reads back as zero.
+		unsafe { memory.data_unchecked_mut()[0] = 1 };
+		allocator.reset_memories();
+		assert_eq!(memory.size(), 2);
+		assert_eq!(unsafe { memory.data_unchecked()[0] }, 0);
+	}
+}
@@ -0,0 +1,125 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! A cross-platform way of returning a [`wasmtime::LinearMemory`]'s resident pages back to the
+//! operating system without giving up the mapping itself.
+
+/// Extension of [`wasmtime::LinearMemory`] implemented by the memories that the `mach_memory` and
+/// `linux_memory` allocators create.
+///
+/// Calling [`reset`](Self::reset) keeps the memory's virtual address, accessible size and guard
+/// pages untouched, but allows the operating system to reclaim the physical pages backing the
+/// accessible region. The next access to those pages will fault in fresh, zeroed memory.
+///
+/// This allows the executor to reuse a single instance across many calls while keeping its
+/// resident memory bounded, instead of tearing down and recreating the instance (and its
+/// mapping) after every call.
+pub trait ResetLinearMemory {
+	/// Return the currently accessible pages of this memory to the operating system.
+	///
+	/// This only affects the accessible region of the memory; the mapping and guard pages are
+	/// left intact. This is a no-op on platforms where this isn't supported.
+	fn reset(&self);
+}
+
+use sc_executor_common::{error::Error, wasm_runtime::WasmInstance};
+use sp_wasm_interface::Value;
+
+/// Wraps a [`WasmInstance`], resetting its linear memory (or memories) after every call.
+///
+/// `MachAllocator`/`LinuxAllocator` only know how to reset the memories *they* created; they
+/// can't reach into wasmtime's instance to do it themselves. This decorator is how the executor
+/// plugs that reset in at the point it actually matters: right after a call returns and before
+/// the instance is handed back out for reuse, so the next call starts from a heap with bounded
+/// resident memory instead of whatever the previous call left dirty.
+pub struct ResettingInstance<I> {
+	instance: I,
+	reset: Box<dyn Fn() + Send + Sync>,
+}
+
+impl<I> ResettingInstance<I> {
+	/// Wrap `instance`, calling `reset` after every `call`/`call_export` it handles.
+	///
+	/// `reset` is typically `{ let allocator = allocator.clone(); move || allocator.reset_memories() }`
+	/// for whichever `MachAllocator`/`LinuxAllocator` created `instance`'s linear memory.
+	pub fn new(instance: I, reset: Box<dyn Fn() + Send + Sync>) -> Self {
+		Self { instance, reset }
+	}
+}
+
+impl<I: WasmInstance> WasmInstance for ResettingInstance<I> {
+	fn call(&mut self, method: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+		let result = self.instance.call(method, data);
+		(self.reset)();
+		result
+	}
+
+	fn call_export(&self, method: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+		let result = self.instance.call_export(method, data);
+		(self.reset)();
+		result
+	}
+
+	fn get_global_const(&mut self, name: &str) -> Result<Option<Value>, Error> {
+		self.instance.get_global_const(name)
+	}
+
+	fn linear_memory_base_ptr(&self) -> Option<*const u8> {
+		self.instance.linear_memory_base_ptr()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	struct CountingInstance;
+
+	impl WasmInstance for CountingInstance {
+		fn call(&mut self, _method: &str, _data: &[u8]) -> Result<Vec<u8>, Error> {
+			Ok(Vec::new())
+		}
+
+		fn call_export(&self, _method: &str, _data: &[u8]) -> Result<Vec<u8>, Error> {
+			Ok(Vec::new())
+		}
+
+		fn get_global_const(&mut self, _name: &str) -> Result<Option<Value>, Error> {
+			Ok(None)
+		}
+	}
+
+	#[test]
+	fn resets_after_call_and_call_export() {
+		let resets = std::sync::Arc::new(AtomicUsize::new(0));
+		let resets_clone = resets.clone();
+		let mut instance =
+			ResettingInstance::new(CountingInstance, Box::new(move || { resets_clone.fetch_add(1, Ordering::SeqCst); }));
+
+		instance.call("entry", &[]).unwrap();
+		assert_eq!(resets.load(Ordering::SeqCst), 1);
+
+		instance.call_export("entry", &[]).unwrap();
+		assert_eq!(resets.load(Ordering::SeqCst), 2);
+
+		// Calls that don't touch the instance's memory, like reading a global, don't reset it.
+		instance.get_global_const("some_global").unwrap();
+		assert_eq!(resets.load(Ordering::SeqCst), 2);
+	}
+}
@@ -25,6 +25,28 @@ pub struct CountedStorageMap<Map, Counter>(
 	core::marker::PhantomData<(Map, Counter)>
 );
 
+/// An iterator that drains a [`CountedStorageMap`] key/value pair by key/value pair, decrementing
+/// the counter for each item actually removed.
+pub struct CountedStorageMapDrain<MapKey, MapValue, CounterPrefix> {
+	drain: crate::storage::PrefixIterator<(MapKey, MapValue)>,
+	phantom: core::marker::PhantomData<CounterPrefix>,
+}
+
+impl<MapKey, MapValue, CounterPrefix> Iterator for CountedStorageMapDrain<MapKey, MapValue, CounterPrefix>
+where
+	CounterPrefix: StorageInstance,
+{
+	type Item = (MapKey, MapValue);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let next = self.drain.next();
+		if next.is_some() {
+			StorageValue::<CounterPrefix, u32, ValueQuery>::mutate(|value| value.saturating_dec());
+		}
+		next
+	}
+}
+
 /// Helper to get access to map and counter of `CountedStorageMap`.
 trait Helper {
 	type Map;
@@ -118,6 +140,27 @@ where
 		<Self as Helper>::Map::insert(key, val)
 	}
 
+	/// Try to insert a value and see if the `CountedStorageMap` can accept it, checking the
+	/// current count against `MapMaxValues` beforehand.
+	///
+	/// Inserting a value for a key that already exists is always accepted, since it doesn't
+	/// change the count. Inserting a value for a new key is rejected with `Err(())` once the map
+	/// is already at `MapMaxValues`; otherwise it behaves exactly like [`Self::insert`].
+	pub fn try_insert<KeyArg: EncodeLike<MapKey> + Clone, ValArg: EncodeLike<MapValue>>(
+		key: KeyArg,
+		val: ValArg,
+	) -> Result<(), ()> {
+		let existed = <Self as Helper>::Map::contains_key(key.clone());
+		if !existed {
+			Self::ensure_can_add_one()?;
+		}
+		<Self as Helper>::Map::insert(key, val);
+		if !existed {
+			<Self as Helper>::Counter::mutate(|value| value.saturating_inc());
+		}
+		Ok(())
+	}
+
 	/// Remove the value under a key.
 	pub fn remove<KeyArg: EncodeLike<MapKey> + Clone>(key: KeyArg) {
 		if <Self as Helper>::Map::contains_key(key.clone()) {
@@ -232,10 +275,37 @@ where
 		<Self as Helper>::Map::migrate_key::<OldHasher, _>(key)
 	}
 
-	/// Remove all value of the storage.
-	pub fn remove_all() {
-		<Self as Helper>::Counter::set(0u32);
-		<Self as Helper>::Map::remove_all()
+	/// Remove items from the map until, at most, `maybe_limit` items have been removed.
+	///
+	/// If `maybe_limit` is `None`, the entire map is wiped in one go and the counter is reset to
+	/// `0`, regardless of the value it held before. This is cheap, but can only be done if the
+	/// whole removal fits within the weight budget of a single block.
+	///
+	/// If `maybe_limit` is `Some(limit)`, at most `limit` entries are removed and the counter is
+	/// decremented by the number of entries actually removed. This variant is meant to be called
+	/// repeatedly, once per block, to clear a map too large to remove in a single block without
+	/// exceeding the weight budget; the counter stays correct across calls since only entries that
+	/// were actually deleted are accounted for.
+	///
+	/// Returns the number of items removed.
+	///
+	/// # Note for callers migrating from the old, argument-less `remove_all`
+	///
+	/// This used to take no arguments and always wipe the whole map; callers doing that should
+	/// pass `remove_all(None)` to keep the previous behaviour.
+	pub fn remove_all(maybe_limit: Option<u32>) -> u32
+	where
+		MapHasher: crate::hash::ReversibleStorageHasher,
+	{
+		match maybe_limit {
+			None => {
+				let count = Self::count();
+				<Self as Helper>::Counter::set(0u32);
+				<Self as Helper>::Map::remove_all();
+				count
+			},
+			Some(limit) => Self::drain().take(limit as usize).count() as u32,
+		}
 	}
 
 	/// Iter over all value of the storage.
@@ -245,6 +315,30 @@ where
 		<Self as Helper>::Map::iter_values()
 	}
 
+	/// Iter over all key/value pairs of the storage.
+	///
+	/// NOTE: If a value failed to decode because storage is corrupted then it is skipped.
+	pub fn iter() -> crate::storage::PrefixIterator<(MapKey, MapValue)>
+	where
+		MapHasher: crate::hash::ReversibleStorageHasher,
+	{
+		<Self as Helper>::Map::iter()
+	}
+
+	/// Remove all key/value pairs of the storage, yielding each one as it is removed and
+	/// decrementing the counter accordingly.
+	///
+	/// NOTE: If a value failed to decode because storage is corrupted then it is skipped.
+	pub fn drain() -> CountedStorageMapDrain<MapKey, MapValue, CounterPrefix>
+	where
+		MapHasher: crate::hash::ReversibleStorageHasher,
+	{
+		CountedStorageMapDrain {
+			drain: <Self as Helper>::Map::drain(),
+			phantom: core::marker::PhantomData,
+		}
+	}
+
 	/// Translate the values of all elements by a function `f`, in the map in no particular order.
 	///
 	/// By returning `None` from `f` for an element, you'll remove it from the map.
@@ -265,7 +359,9 @@ where
 
 	/// Try and append the given item to the value in the storage.
 	///
-	/// Is only available if `MapValue` of the storage implements [`StorageTryAppend`].
+	/// Is only available if `MapValue` of the storage implements [`StorageTryAppend`]. An append
+	/// that would create a new entry is rejected with `Err(())` once the map is already at
+	/// `MapMaxValues`.
 	pub fn try_append<KArg, Item, EncodeLikeItem>(
 		key: KArg,
 		item: EncodeLikeItem,
@@ -276,10 +372,26 @@ where
 		EncodeLikeItem: EncodeLike<Item>,
 		MapValue: StorageTryAppend<Item>,
 	{
-		todo!()
-		// <
-		// 	Self as crate::storage::TryAppendMap<MapKey, MapValue, Item>
-		// >::try_append(key, item)
+		let existed = <Self as Helper>::Map::contains_key(key.clone());
+		if !existed {
+			Self::ensure_can_add_one()?;
+		}
+		<Self as Helper>::Map::try_append(key, item)?;
+		if !existed {
+			<Self as Helper>::Counter::mutate(|value| value.saturating_inc());
+		}
+		Ok(())
+	}
+
+	/// Check whether a value for a key that doesn't yet exist can still be added, given
+	/// `MapMaxValues` and the live [`Self::count`].
+	fn ensure_can_add_one() -> Result<(), ()> {
+		if let Some(max) = MapMaxValues::get() {
+			if Self::count() >= max {
+				return Err(())
+			}
+		}
+		Ok(())
 	}
 
 	/// Initialize the counter with the actual number of items in the map.
@@ -299,3 +411,149 @@ where
 		<Self as Helper>::Counter::get()
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use sp_io::TestExternalities;
+	use crate::storage::types::ValueQuery;
+	use crate::hash::Twox64Concat;
+
+	struct Prefix;
+	impl StorageInstance for Prefix {
+		fn pallet_prefix() -> &'static str { "test" }
+		const STORAGE_PREFIX: &'static str = "foo";
+	}
+
+	struct CounterPrefix;
+	impl StorageInstance for CounterPrefix {
+		fn pallet_prefix() -> &'static str { "test" }
+		const STORAGE_PREFIX: &'static str = "counter_for_foo";
+	}
+
+	struct MaxValues;
+	impl Get<Option<u32>> for MaxValues {
+		fn get() -> Option<u32> {
+			Some(3)
+		}
+	}
+
+	type Foo = CountedStorageMap<
+		StorageMap<Prefix, Twox64Concat, u16, u32, ValueQuery, crate::traits::GetDefault, MaxValues>,
+		StorageValue<CounterPrefix, u32, ValueQuery>,
+	>;
+
+	#[test]
+	fn try_insert_respects_map_max_values() {
+		TestExternalities::default().execute_with(|| {
+			Foo::try_insert(1, 10).unwrap();
+			Foo::try_insert(2, 20).unwrap();
+			Foo::try_insert(3, 30).unwrap();
+			assert_eq!(Foo::count(), 3);
+
+			// At capacity: a new key is rejected.
+			assert!(Foo::try_insert(4, 40).is_err());
+			assert_eq!(Foo::count(), 3);
+
+			// Re-inserting an existing key never changes the count, so it's always accepted.
+			Foo::try_insert(1, 11).unwrap();
+			assert_eq!(Foo::count(), 3);
+			assert_eq!(Foo::get(1), 11);
+		});
+	}
+
+	#[test]
+	fn try_append_tracks_fresh_vs_existing_key() {
+		TestExternalities::default().execute_with(|| {
+			struct Bound;
+			impl Get<u32> for Bound {
+				fn get() -> u32 {
+					8
+				}
+			}
+
+			type FooAppend = CountedStorageMap<
+				StorageMap<
+					Prefix,
+					Twox64Concat,
+					u16,
+					crate::storage::bounded_vec::BoundedVec<u32, Bound>,
+					ValueQuery,
+					crate::traits::GetDefault,
+					MaxValues,
+				>,
+				StorageValue<CounterPrefix, u32, ValueQuery>,
+			>;
+
+			// A fresh key increments the counter once, regardless of how many items get appended.
+			FooAppend::try_append(1, 10).unwrap();
+			assert_eq!(FooAppend::count(), 1);
+			FooAppend::try_append(1, 11).unwrap();
+			assert_eq!(FooAppend::count(), 1);
+			assert_eq!(*FooAppend::get(1), vec![10, 11]);
+
+			FooAppend::try_append(2, 20).unwrap();
+			FooAppend::try_append(3, 30).unwrap();
+			assert_eq!(FooAppend::count(), 3);
+
+			// At capacity, appending to a new key is rejected; appending to an existing one is not.
+			assert!(FooAppend::try_append(4, 40).is_err());
+			FooAppend::try_append(1, 12).unwrap();
+			assert_eq!(FooAppend::count(), 3);
+			assert_eq!(*FooAppend::get(1), vec![10, 11, 12]);
+		});
+	}
+
+	#[test]
+	fn iter_and_drain_keep_the_counter_correct() {
+		TestExternalities::default().execute_with(|| {
+			Foo::insert(1, 10);
+			Foo::insert(2, 20);
+			Foo::insert(3, 30);
+			assert_eq!(Foo::count(), 3);
+			assert_eq!(Foo::iter().count(), 3);
+
+			// Draining one item removes exactly it, and decrements the counter by one.
+			let (key, _value) = Foo::drain().next().unwrap();
+			assert_eq!(Foo::get(key), Default::default());
+			assert_eq!(Foo::count(), 2);
+
+			// Draining the rest empties the map and zeroes the counter.
+			assert_eq!(Foo::drain().count(), 2);
+			assert_eq!(Foo::count(), 0);
+			assert_eq!(Foo::iter().count(), 0);
+		});
+	}
+
+	#[test]
+	fn remove_all_none_wipes_everything_at_once() {
+		TestExternalities::default().execute_with(|| {
+			Foo::insert(1, 10);
+			Foo::insert(2, 20);
+			Foo::insert(3, 30);
+
+			assert_eq!(Foo::remove_all(None), 3);
+			assert_eq!(Foo::count(), 0);
+			assert_eq!(Foo::iter().count(), 0);
+		});
+	}
+
+	#[test]
+	fn remove_all_some_can_be_called_repeatedly_until_empty() {
+		TestExternalities::default().execute_with(|| {
+			Foo::insert(1, 10);
+			Foo::insert(2, 20);
+			Foo::insert(3, 30);
+
+			assert_eq!(Foo::remove_all(Some(2)), 2);
+			assert_eq!(Foo::count(), 1);
+
+			assert_eq!(Foo::remove_all(Some(2)), 1);
+			assert_eq!(Foo::count(), 0);
+
+			// Once empty, further calls remove nothing and the counter stays put.
+			assert_eq!(Foo::remove_all(Some(2)), 0);
+			assert_eq!(Foo::count(), 0);
+		});
+	}
+}